@@ -1,16 +1,336 @@
 use clap::{App, Arg, SubCommand};
-use libc;
-use std::ffi::{CString, OsString};
+use std::ffi::OsString;
 use std::io;
 use std::path::PathBuf;
 use std::thread;
 use std::time::{Duration, SystemTime};
 
+#[cfg(target_os = "linux")]
+use std::collections::HashMap;
+
 use csv::{self, WriterBuilder};
-use proc_mounts::{self, MountIter};
-use procfs;
 use serde::{Deserialize, Serialize};
 
+#[cfg(target_os = "linux")]
+use proc_mounts::{self, MountIter, SwapIter};
+#[cfg(target_os = "linux")]
+use std::ffi::CString;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Format {
+    Csv,
+    Influx,
+}
+
+impl Format {
+    pub fn from_arg(s: &str) -> Format {
+        match s {
+            "influx" => Format::Influx,
+            "csv" => Format::Csv,
+            other => {
+                eprintln!("Unknown format ({}), using csv", other);
+                Format::Csv
+            }
+        }
+    }
+}
+
+// Escape a value destined for a line-protocol tag or measurement name:
+// commas, spaces and equals signs must be backslash-escaped.
+fn influx_escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace(' ', "\\ ")
+        .replace('=', "\\=")
+}
+
+// Quote and escape a value destined for a line-protocol string field.
+fn influx_quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// Line-protocol wants the timestamp in nanoseconds; our samples are in ms.
+fn influx_time_ns(time_ms: Option<u128>) -> String {
+    match time_ms {
+        Some(ms) => format!(" {}", ms * 1_000_000),
+        None => String::new(),
+    }
+}
+
+/// Memory figures as reported by the backend, in bytes.
+pub struct MemSample {
+    pub total: u64,
+    pub free: u64,
+    pub buffers: u64,
+    pub cached: u64,
+}
+
+/// 1/5/15 minute load averages.
+pub struct LoadSample {
+    pub one: f32,
+    pub five: f32,
+    pub fifteen: f32,
+}
+
+/// A single process as seen by the backend.
+pub struct ProcSample {
+    pub pid: i32,
+    pub owner: u32,
+    pub open_fd_count: i64,
+    pub num_threads: i64,
+    pub starttime: i64,
+    pub utime: u64,
+    pub stime: u64,
+    pub cmdline: String,
+}
+
+/// A single mounted filesystem as seen by the backend.
+pub struct MountSample {
+    pub source: PathBuf,
+    pub dest: PathBuf,
+    pub fstype: String,
+    pub options: Vec<String>,
+    pub dump: i32,
+    pub pass: i32,
+}
+
+/// Abstracts the OS-specific source of system statistics so the same CSV /
+/// line-protocol schema can be produced on Linux (procfs/libc) and on other
+/// platforms (sysinfo).
+pub trait Platform {
+    fn mem_info(&self) -> Option<MemSample>;
+    fn load_avg(&self) -> Option<LoadSample>;
+    fn processes(&self) -> Vec<ProcSample>;
+    fn mounts(&self) -> Vec<MountSample>;
+    fn fs_usage(&self, mount_point: &str) -> (u64, u64, u64, u32);
+}
+
+/// Returns the backend appropriate for the host OS.
+pub fn default_platform() -> Box<dyn Platform> {
+    #[cfg(target_os = "linux")]
+    {
+        Box::new(LinuxPlatform)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        Box::new(SysinfoPlatform::new())
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub struct LinuxPlatform;
+
+#[cfg(target_os = "linux")]
+impl Platform for LinuxPlatform {
+    fn mem_info(&self) -> Option<MemSample> {
+        match procfs::Meminfo::new() {
+            Ok(mi) => Some(MemSample {
+                total: mi.mem_total,
+                free: mi.mem_free,
+                buffers: mi.buffers,
+                cached: mi.cached,
+            }),
+            Err(err) => {
+                eprintln!("Error loading meminfo: {}", err);
+                None
+            }
+        }
+    }
+
+    fn load_avg(&self) -> Option<LoadSample> {
+        match procfs::LoadAverage::new() {
+            Ok(la) => Some(LoadSample {
+                one: la.one,
+                five: la.five,
+                fifteen: la.fifteen,
+            }),
+            Err(err) => {
+                eprintln!("Error loading load avg: {}", err);
+                None
+            }
+        }
+    }
+
+    fn processes(&self) -> Vec<ProcSample> {
+        let processes = match procfs::process::all_processes() {
+            Ok(processes) => processes,
+            Err(err) => {
+                eprintln!("Error loading processes: {}", err);
+                Vec::new()
+            }
+        };
+        processes
+            .iter()
+            .map(|proc| {
+                let open_fd_count = match proc.fd() {
+                    Ok(fds) => fds.len() as i64,
+                    Err(_) => -1,
+                };
+                let cmdline = match proc.cmdline() {
+                    Ok(items) => {
+                        if items.is_empty() {
+                            String::from("?")
+                        } else {
+                            items.join(" ")
+                        }
+                    }
+                    Err(_) => String::from("?"),
+                };
+                ProcSample {
+                    pid: proc.stat.pid,
+                    owner: proc.owner,
+                    open_fd_count,
+                    num_threads: proc.stat.num_threads,
+                    starttime: proc.stat.starttime as i64,
+                    utime: proc.stat.utime,
+                    stime: proc.stat.stime,
+                    cmdline,
+                }
+            })
+            .collect()
+    }
+
+    fn mounts(&self) -> Vec<MountSample> {
+        let mut mounts = Vec::new();
+        match MountIter::new() {
+            Ok(mount_iter) => {
+                for mount in mount_iter {
+                    match mount {
+                        Ok(proc_mounts::MountInfo {
+                            source,
+                            dest,
+                            fstype,
+                            options,
+                            dump,
+                            pass,
+                        }) => mounts.push(MountSample {
+                            source,
+                            dest,
+                            fstype,
+                            options,
+                            dump,
+                            pass,
+                        }),
+                        Err(err) => eprintln!("Error reading mount info: {}", err),
+                    }
+                }
+            }
+            Err(err) => eprintln!("Error reading mount info: {}", err),
+        }
+        mounts
+    }
+
+    fn fs_usage(&self, mount_point: &str) -> (u64, u64, u64, u32) {
+        fs_usage(mount_point)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub struct SysinfoPlatform {
+    // interior mutability so the snapshot can be refreshed on every read while
+    // the loops hold the backend behind a shared `&dyn Platform` reference.
+    system: std::cell::RefCell<sysinfo::System>,
+}
+
+#[cfg(not(target_os = "linux"))]
+impl SysinfoPlatform {
+    pub fn new() -> SysinfoPlatform {
+        use sysinfo::SystemExt;
+        SysinfoPlatform {
+            system: std::cell::RefCell::new(sysinfo::System::new_all()),
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl Platform for SysinfoPlatform {
+    fn mem_info(&self) -> Option<MemSample> {
+        use sysinfo::SystemExt;
+        let mut system = self.system.borrow_mut();
+        system.refresh_memory();
+        // `MemSample` is in bytes (the Linux path feeds it procfs Meminfo
+        // values, which procfs reports in bytes). sysinfo >= 0.23 reports
+        // memory in bytes too, so the values pass through unchanged.
+        Some(MemSample {
+            total: system.total_memory(),
+            free: system.free_memory(),
+            // sysinfo does not break out buffers/cached separately.
+            buffers: 0,
+            cached: 0,
+        })
+    }
+
+    fn load_avg(&self) -> Option<LoadSample> {
+        use sysinfo::SystemExt;
+        let la = self.system.borrow().load_average();
+        Some(LoadSample {
+            one: la.one as f32,
+            five: la.five as f32,
+            fifteen: la.fifteen as f32,
+        })
+    }
+
+    fn processes(&self) -> Vec<ProcSample> {
+        use sysinfo::{ProcessExt, SystemExt};
+        let mut system = self.system.borrow_mut();
+        system.refresh_processes();
+        system
+            .processes()
+            .iter()
+            .map(|(pid, proc)| ProcSample {
+                pid: *pid as i32,
+                owner: 0,
+                open_fd_count: -1,
+                num_threads: -1,
+                starttime: proc.start_time() as i64,
+                utime: 0,
+                stime: 0,
+                cmdline: proc.cmd().join(" "),
+            })
+            .collect()
+    }
+
+    fn mounts(&self) -> Vec<MountSample> {
+        use sysinfo::{DiskExt, SystemExt};
+        let mut system = self.system.borrow_mut();
+        system.refresh_disks_list();
+        system
+            .disks()
+            .iter()
+            .map(|disk| MountSample {
+                source: PathBuf::from(disk.name()),
+                dest: disk.mount_point().to_path_buf(),
+                fstype: String::from_utf8_lossy(disk.file_system()).into_owned(),
+                options: Vec::new(),
+                dump: 0,
+                pass: 0,
+            })
+            .collect()
+    }
+
+    fn fs_usage(&self, mount_point: &str) -> (u64, u64, u64, u32) {
+        use sysinfo::{DiskExt, SystemExt};
+        let mut system = self.system.borrow_mut();
+        system.refresh_disks();
+        for disk in system.disks() {
+            if disk.mount_point().to_string_lossy() == mount_point {
+                let total = disk.total_space() / 1024;
+                let available = disk.available_space() / 1024;
+                let used = total.saturating_sub(available);
+                let nonroot_total = used + available;
+                let pct = if nonroot_total == 0 {
+                    0
+                } else {
+                    used * 100 / nonroot_total
+                };
+                return (used, available, total, pct as u32);
+            }
+        }
+        (0, 0, 0, 100)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct SysInfo {
     id: String,
@@ -22,29 +342,30 @@ struct SysInfo {
     load_avg_1: Option<f32>,
     load_avg_5: Option<f32>,
     load_avg_15: Option<f32>,
+    cpu_busy_pct: Option<f32>,
+    cpu_per_core: Option<String>,
 }
 
 impl SysInfo {
-    pub fn new(id: String) -> SysInfo {
-        let (mem_total, mem_free, mem_buffers, mem_cached) = match procfs::meminfo() {
-            Ok(mi) => (
-                Some(mi.mem_total),
-                Some(mi.mem_free),
+    pub fn new(
+        id: String,
+        cpu_busy_pct: Option<f32>,
+        cpu_per_core: Option<String>,
+        platform: &dyn Platform,
+    ) -> SysInfo {
+        let (mem_total, mem_free, mem_buffers, mem_cached) = match platform.mem_info() {
+            Some(mi) => (
+                Some(mi.total),
+                Some(mi.free),
                 Some(mi.buffers),
                 Some(mi.cached),
             ),
-            Err(err) => {
-                eprintln!("Error loading meminfo: {}", err);
-                (None, None, None, None)
-            }
+            None => (None, None, None, None),
         };
 
-        let (load_avg_1, load_avg_5, load_avg_15) = match procfs::LoadAverage::new() {
-            Ok(la) => (Some(la.one), Some(la.five), Some(la.fifteen)),
-            Err(err) => {
-                eprintln!("Error loading load avg: {}", err);
-                (None, None, None)
-            }
+        let (load_avg_1, load_avg_5, load_avg_15) = match platform.load_avg() {
+            Some(la) => (Some(la.one), Some(la.five), Some(la.fifteen)),
+            None => (None, None, None),
         };
 
         let time_ms = timestamp();
@@ -59,6 +380,8 @@ impl SysInfo {
             load_avg_1,
             load_avg_5,
             load_avg_15,
+            cpu_busy_pct,
+            cpu_per_core,
         }
     }
 
@@ -70,6 +393,93 @@ impl SysInfo {
             .from_writer(handle);
         wtr.serialize(self)
     }
+
+    // Returns None when no field is populated: line protocol requires at least
+    // one field, so a field-less point (e.g. a backend that returned nothing)
+    // must be skipped rather than emitted as `sys,id=localhost  <ts>`.
+    pub fn to_influx(&self) -> Option<String> {
+        let mut fields: Vec<String> = Vec::new();
+        if let Some(v) = self.mem_total {
+            fields.push(format!("mem_total={}i", v));
+        }
+        if let Some(v) = self.mem_free {
+            fields.push(format!("mem_free={}i", v));
+        }
+        if let Some(v) = self.mem_buffers {
+            fields.push(format!("mem_buffers={}i", v));
+        }
+        if let Some(v) = self.mem_cached {
+            fields.push(format!("mem_cached={}i", v));
+        }
+        if let Some(v) = self.load_avg_1 {
+            fields.push(format!("load_avg_1={}", v));
+        }
+        if let Some(v) = self.load_avg_5 {
+            fields.push(format!("load_avg_5={}", v));
+        }
+        if let Some(v) = self.load_avg_15 {
+            fields.push(format!("load_avg_15={}", v));
+        }
+        if let Some(v) = self.cpu_busy_pct {
+            fields.push(format!("cpu_busy_pct={}", v));
+        }
+        if let Some(v) = &self.cpu_per_core {
+            fields.push(format!("cpu_per_core={}", influx_quote(v)));
+        }
+
+        if fields.is_empty() {
+            return None;
+        }
+
+        Some(format!(
+            "sys,id={} {}{}",
+            influx_escape(&self.id),
+            fields.join(","),
+            influx_time_ns(self.time_ms)
+        ))
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn cpu_time_total(t: &procfs::CpuTime) -> u64 {
+    t.user
+        + t.nice
+        + t.system
+        + t.idle
+        + t.iowait.unwrap_or(0)
+        + t.irq.unwrap_or(0)
+        + t.softirq.unwrap_or(0)
+        + t.steal.unwrap_or(0)
+}
+
+#[cfg(target_os = "linux")]
+fn cpu_time_idle(t: &procfs::CpuTime) -> u64 {
+    t.idle + t.iowait.unwrap_or(0)
+}
+
+// busy% between two /proc/stat snapshots: everything but idle and iowait,
+// as a fraction of the jiffies that elapsed between the samples.
+#[cfg(target_os = "linux")]
+fn busy_pct(prev: &procfs::CpuTime, cur: &procfs::CpuTime) -> f32 {
+    let total = cpu_time_total(cur).saturating_sub(cpu_time_total(prev));
+    let idle = cpu_time_idle(cur).saturating_sub(cpu_time_idle(prev));
+    if total == 0 {
+        0.0
+    } else {
+        (total - idle) as f32 / total as f32 * 100.0
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn cpu_utilization(prev: &procfs::KernelStats, cur: &procfs::KernelStats) -> (f32, Vec<f32>) {
+    let aggregate = busy_pct(&prev.total, &cur.total);
+    let per_core = prev
+        .cpu_time
+        .iter()
+        .zip(cur.cpu_time.iter())
+        .map(|(p, c)| busy_pct(p, c))
+        .collect();
+    (aggregate, per_core)
 }
 
 fn timestamp() -> Option<u128> {
@@ -82,15 +492,66 @@ fn timestamp() -> Option<u128> {
     }
 }
 
-pub fn sys_stats_loop(id: &str, interval: &Duration) {
+// CPU utilization is derived from two /proc/stat snapshots, which is Linux
+// specific; the previous sample type therefore differs per platform.
+#[cfg(target_os = "linux")]
+type CpuPrev = Option<procfs::KernelStats>;
+#[cfg(not(target_os = "linux"))]
+type CpuPrev = Option<()>;
+
+#[cfg(target_os = "linux")]
+fn sample_cpu(prev: &mut CpuPrev) -> (Option<f32>, Option<String>) {
+    let cur = match procfs::KernelStats::new() {
+        Ok(ks) => Some(ks),
+        Err(err) => {
+            eprintln!("Error loading kernel stats: {}", err);
+            None
+        }
+    };
+
+    let out = match (prev.as_ref(), cur.as_ref()) {
+        (Some(prev), Some(cur)) => {
+            let (aggregate, per_core) = cpu_utilization(prev, cur);
+            let per_core = per_core
+                .iter()
+                .map(|pct| format!("{:.2}", pct))
+                .collect::<Vec<_>>()
+                .join(";");
+            (Some(aggregate), Some(per_core))
+        }
+        _ => (None, None),
+    };
+
+    *prev = cur;
+    out
+}
+
+#[cfg(not(target_os = "linux"))]
+fn sample_cpu(_prev: &mut CpuPrev) -> (Option<f32>, Option<String>) {
+    (None, None)
+}
+
+pub fn sys_stats_loop(id: &str, interval: &Duration, format: Format, platform: &dyn Platform) {
     let mut has_headers = true;
+    // CPU utilization is a rate, so we keep the previous /proc/stat snapshot
+    // and diff against it; the first iteration has no prior sample.
+    let mut prev_cpu: CpuPrev = None;
 
     loop {
-        let sys_info = SysInfo::new(String::from(id));
-        match sys_info.write_stdout(has_headers) {
-            Ok(_) => {}
-            Err(err) => {
-                eprintln!("Error writing stats: {}", err);
+        let (cpu_busy_pct, cpu_per_core) = sample_cpu(&mut prev_cpu);
+
+        let sys_info = SysInfo::new(String::from(id), cpu_busy_pct, cpu_per_core, platform);
+        match format {
+            Format::Csv => match sys_info.write_stdout(has_headers) {
+                Ok(_) => {}
+                Err(err) => {
+                    eprintln!("Error writing stats: {}", err);
+                }
+            },
+            Format::Influx => {
+                if let Some(line) = sys_info.to_influx() {
+                    println!("{}", line);
+                }
             }
         }
 
@@ -113,58 +574,358 @@ struct ProcInfo {
 }
 
 impl ProcInfo {
-    pub fn new(time_ms: Option<u128>, proc: &procfs::Process) -> ProcInfo {
-        let open_fd_count = match proc.fd() {
-            Ok(fds) => fds.len() as i64,
-            Err(_) => -1,
-        };
-
-        let cmdline = match proc.cmdline() {
-            Ok(items) => {
-                if items.len() == 0 {
-                    String::from("?")
-                } else {
-                    items.join(" ")
-                }
-            }
-            Err(_) => String::from("?"),
-        };
-
+    pub fn new(time_ms: Option<u128>, proc: &ProcSample) -> ProcInfo {
         ProcInfo {
             time_ms,
-            pid: proc.stat.pid,
+            pid: proc.pid,
             owner: proc.owner,
-            open_fd_count,
-            num_threads: proc.stat.num_threads,
-            starttime: proc.stat.starttime,
-            utime: proc.stat.utime,
-            stime: proc.stat.stime,
-            cmdline: cmdline,
+            open_fd_count: proc.open_fd_count,
+            num_threads: proc.num_threads,
+            starttime: proc.starttime,
+            utime: proc.utime,
+            stime: proc.stime,
+            cmdline: proc.cmdline.clone(),
         }
     }
+
+    pub fn to_influx(&self) -> String {
+        let fields = [
+            format!("open_fd_count={}i", self.open_fd_count),
+            format!("num_threads={}i", self.num_threads),
+            format!("starttime={}i", self.starttime),
+            format!("utime={}i", self.utime),
+            format!("stime={}i", self.stime),
+            format!("cmdline={}", influx_quote(&self.cmdline)),
+        ];
+
+        format!(
+            "proc,pid={},owner={} {}{}",
+            self.pid,
+            self.owner,
+            fields.join(","),
+            influx_time_ns(self.time_ms)
+        )
+    }
 }
 
-pub fn fd_stats_loop(interval: &Duration) {
+pub fn fd_stats_loop(interval: &Duration, format: Format, platform: &dyn Platform) {
     loop {
         let time_ms = timestamp();
         let stdout = io::stdout();
         let handle = stdout.lock();
         let mut wtr = WriterBuilder::new().has_headers(true).from_writer(handle);
-        for process in procfs::all_processes() {
+        for process in platform.processes() {
             let proc_info = ProcInfo::new(time_ms, &process);
-            match wtr.serialize(proc_info) {
-                Ok(_) => {}
-                Err(err) => {
-                    eprintln!("Error serializing proc_info: {}", err);
+            match format {
+                Format::Csv => match wtr.serialize(proc_info) {
+                    Ok(_) => {}
+                    Err(err) => {
+                        eprintln!("Error serializing proc_info: {}", err);
+                    }
+                },
+                Format::Influx => println!("{}", proc_info.to_influx()),
+            }
+        }
+
+        let _ = wtr.flush();
+        thread::sleep(*interval);
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[derive(Debug, Serialize, Deserialize)]
+struct NetInfo {
+    time_ms: Option<u128>,
+    iface: String,
+    rx_bytes: u64,
+    rx_packets: u64,
+    rx_errs: u64,
+    rx_drop: u64,
+    tx_bytes: u64,
+    tx_packets: u64,
+    tx_errs: u64,
+    tx_drop: u64,
+    rx_bytes_per_sec: Option<f64>,
+    tx_bytes_per_sec: Option<f64>,
+    rx_packets_per_sec: Option<f64>,
+    tx_packets_per_sec: Option<f64>,
+}
+
+// counters are cumulative since boot, so a per-second rate needs the prior
+// sample and the elapsed interval.
+#[cfg(target_os = "linux")]
+fn per_sec(cur: u64, prev: u64, interval: &Duration) -> f64 {
+    let secs = interval.as_secs_f64();
+    if secs == 0.0 {
+        0.0
+    } else {
+        cur.saturating_sub(prev) as f64 / secs
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl NetInfo {
+    pub fn new(
+        time_ms: Option<u128>,
+        cur: &procfs::net::DeviceStatus,
+        prev: Option<&procfs::net::DeviceStatus>,
+        interval: &Duration,
+    ) -> NetInfo {
+        let (rx_bytes_per_sec, tx_bytes_per_sec, rx_packets_per_sec, tx_packets_per_sec) =
+            match prev {
+                Some(prev) => (
+                    Some(per_sec(cur.recv_bytes, prev.recv_bytes, interval)),
+                    Some(per_sec(cur.sent_bytes, prev.sent_bytes, interval)),
+                    Some(per_sec(cur.recv_packets, prev.recv_packets, interval)),
+                    Some(per_sec(cur.sent_packets, prev.sent_packets, interval)),
+                ),
+                None => (None, None, None, None),
+            };
+
+        NetInfo {
+            time_ms,
+            iface: cur.name.clone(),
+            rx_bytes: cur.recv_bytes,
+            rx_packets: cur.recv_packets,
+            rx_errs: cur.recv_errs,
+            rx_drop: cur.recv_drop,
+            tx_bytes: cur.sent_bytes,
+            tx_packets: cur.sent_packets,
+            tx_errs: cur.sent_errs,
+            tx_drop: cur.sent_drop,
+            rx_bytes_per_sec,
+            tx_bytes_per_sec,
+            rx_packets_per_sec,
+            tx_packets_per_sec,
+        }
+    }
+
+    pub fn to_influx(&self) -> String {
+        let mut fields = vec![
+            format!("rx_bytes={}i", self.rx_bytes),
+            format!("rx_packets={}i", self.rx_packets),
+            format!("rx_errs={}i", self.rx_errs),
+            format!("rx_drop={}i", self.rx_drop),
+            format!("tx_bytes={}i", self.tx_bytes),
+            format!("tx_packets={}i", self.tx_packets),
+            format!("tx_errs={}i", self.tx_errs),
+            format!("tx_drop={}i", self.tx_drop),
+        ];
+        if let Some(v) = self.rx_bytes_per_sec {
+            fields.push(format!("rx_bytes_per_sec={}", v));
+        }
+        if let Some(v) = self.tx_bytes_per_sec {
+            fields.push(format!("tx_bytes_per_sec={}", v));
+        }
+        if let Some(v) = self.rx_packets_per_sec {
+            fields.push(format!("rx_packets_per_sec={}", v));
+        }
+        if let Some(v) = self.tx_packets_per_sec {
+            fields.push(format!("tx_packets_per_sec={}", v));
+        }
+
+        format!(
+            "net,iface={} {}{}",
+            influx_escape(&self.iface),
+            fields.join(","),
+            influx_time_ns(self.time_ms)
+        )
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub fn net_stats_loop(interval: &Duration, format: Format) {
+    let mut has_headers = true;
+    // interface counters are cumulative, so we hold the previous sample to
+    // turn them into per-second rates.
+    let mut prev: Option<HashMap<String, procfs::net::DeviceStatus>> = None;
+
+    loop {
+        let time_ms = timestamp();
+        let cur = match procfs::net::dev_status() {
+            Ok(devs) => Some(devs),
+            Err(err) => {
+                eprintln!("Error reading net dev status: {}", err);
+                None
+            }
+        };
+
+        if let Some(cur) = &cur {
+            let stdout = io::stdout();
+            let handle = stdout.lock();
+            let mut wtr = WriterBuilder::new()
+                .has_headers(has_headers)
+                .from_writer(handle);
+            for (name, dev) in cur {
+                let prev_dev = prev.as_ref().and_then(|p| p.get(name));
+                let net_info = NetInfo::new(time_ms, dev, prev_dev, interval);
+                match format {
+                    Format::Csv => match wtr.serialize(net_info) {
+                        Ok(_) => {}
+                        Err(err) => {
+                            eprintln!("Error serializing net_info: {}", err);
+                        }
+                    },
+                    Format::Influx => println!("{}", net_info.to_influx()),
                 }
             }
+            let _ = wtr.flush();
         }
 
-        match wtr.flush() {
-            Ok(_) => {}
-            Err(_) => {}
+        prev = cur;
+        thread::sleep(*interval);
+        has_headers = false;
+    }
+}
+
+// a disk sector is 512 bytes regardless of the device's logical block size.
+#[cfg(target_os = "linux")]
+const SECTOR_BYTES: u64 = 512;
+
+#[cfg(target_os = "linux")]
+#[derive(Debug, Serialize, Deserialize)]
+struct DiskInfo {
+    time_ms: Option<u128>,
+    device: String,
+    reads: u64,
+    sectors_read: u64,
+    read_time_ms: u64,
+    writes: u64,
+    sectors_written: u64,
+    write_time_ms: u64,
+    io_in_progress: u64,
+    weighted_io_ms: u64,
+    read_iops: Option<f64>,
+    write_iops: Option<f64>,
+    read_bytes_per_sec: Option<f64>,
+    write_bytes_per_sec: Option<f64>,
+}
+
+#[cfg(target_os = "linux")]
+impl DiskInfo {
+    pub fn new(
+        time_ms: Option<u128>,
+        cur: &procfs::DiskStat,
+        prev: Option<&procfs::DiskStat>,
+        interval: &Duration,
+    ) -> DiskInfo {
+        let (read_iops, write_iops, read_bytes_per_sec, write_bytes_per_sec) = match prev {
+            Some(prev) => (
+                Some(per_sec(cur.reads as u64, prev.reads as u64, interval)),
+                Some(per_sec(cur.writes as u64, prev.writes as u64, interval)),
+                Some(per_sec(
+                    cur.sectors_read as u64 * SECTOR_BYTES,
+                    prev.sectors_read as u64 * SECTOR_BYTES,
+                    interval,
+                )),
+                Some(per_sec(
+                    cur.sectors_written as u64 * SECTOR_BYTES,
+                    prev.sectors_written as u64 * SECTOR_BYTES,
+                    interval,
+                )),
+            ),
+            None => (None, None, None, None),
+        };
+
+        DiskInfo {
+            time_ms,
+            device: cur.name.clone(),
+            reads: cur.reads as u64,
+            sectors_read: cur.sectors_read as u64,
+            read_time_ms: cur.time_reading as u64,
+            writes: cur.writes as u64,
+            sectors_written: cur.sectors_written as u64,
+            write_time_ms: cur.time_writing as u64,
+            io_in_progress: cur.in_progress as u64,
+            weighted_io_ms: cur.weighted_time_in_progress as u64,
+            read_iops,
+            write_iops,
+            read_bytes_per_sec,
+            write_bytes_per_sec,
+        }
+    }
+
+    pub fn to_influx(&self) -> String {
+        let mut fields = vec![
+            format!("reads={}i", self.reads),
+            format!("sectors_read={}i", self.sectors_read),
+            format!("read_time_ms={}i", self.read_time_ms),
+            format!("writes={}i", self.writes),
+            format!("sectors_written={}i", self.sectors_written),
+            format!("write_time_ms={}i", self.write_time_ms),
+            format!("io_in_progress={}i", self.io_in_progress),
+            format!("weighted_io_ms={}i", self.weighted_io_ms),
+        ];
+        if let Some(v) = self.read_iops {
+            fields.push(format!("read_iops={}", v));
+        }
+        if let Some(v) = self.write_iops {
+            fields.push(format!("write_iops={}", v));
+        }
+        if let Some(v) = self.read_bytes_per_sec {
+            fields.push(format!("read_bytes_per_sec={}", v));
+        }
+        if let Some(v) = self.write_bytes_per_sec {
+            fields.push(format!("write_bytes_per_sec={}", v));
+        }
+
+        format!(
+            "disk,device={} {}{}",
+            influx_escape(&self.device),
+            fields.join(","),
+            influx_time_ns(self.time_ms)
+        )
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub fn disk_stats_loop(interval: &Duration, format: Format) {
+    let mut has_headers = true;
+    // diskstats are cumulative counters, so we keep the previous sample to
+    // derive IOPS and throughput rates.
+    let mut prev: Option<HashMap<String, procfs::DiskStat>> = None;
+
+    loop {
+        let time_ms = timestamp();
+        let cur = match procfs::diskstats() {
+            Ok(stats) => {
+                let mut map = HashMap::new();
+                for stat in stats {
+                    map.insert(stat.name.clone(), stat);
+                }
+                Some(map)
+            }
+            Err(err) => {
+                eprintln!("Error reading diskstats: {}", err);
+                None
+            }
+        };
+
+        if let Some(cur) = &cur {
+            let stdout = io::stdout();
+            let handle = stdout.lock();
+            let mut wtr = WriterBuilder::new()
+                .has_headers(has_headers)
+                .from_writer(handle);
+            for (name, stat) in cur {
+                let prev_stat = prev.as_ref().and_then(|p| p.get(name));
+                let disk_info = DiskInfo::new(time_ms, stat, prev_stat, interval);
+                match format {
+                    Format::Csv => match wtr.serialize(disk_info) {
+                        Ok(_) => {}
+                        Err(err) => {
+                            eprintln!("Error serializing disk_info: {}", err);
+                        }
+                    },
+                    Format::Influx => println!("{}", disk_info.to_influx()),
+                }
+            }
+            let _ = wtr.flush();
         }
+
+        prev = cur;
         thread::sleep(*interval);
+        has_headers = false;
     }
 }
 
@@ -193,6 +954,67 @@ pub struct MountInfo {
     use_pc: u32,
 }
 
+/// Unified, tagged row for the `mount` subcommand. Filesystem and swap records
+/// have different shapes, so they share one stable CSV schema distinguished by
+/// the `kind` column; fields that don't apply to a record type are left empty.
+#[derive(Debug, Serialize, Deserialize)]
+struct FsRow {
+    time_ms: Option<u128>,
+    kind: String,
+    source: String,
+    dest: String,
+    fstype: String,
+    options: String,
+    dump: Option<i32>,
+    pass: Option<i32>,
+    used: u64,
+    available: Option<u64>,
+    total: u64,
+    use_pc: Option<u32>,
+    size: Option<u64>,
+    priority: Option<isize>,
+}
+
+impl FsRow {
+    fn from_mount(m: MountInfo) -> FsRow {
+        FsRow {
+            time_ms: m.time_ms,
+            kind: String::from("mount"),
+            source: m.source,
+            dest: m.dest,
+            fstype: m.fstype,
+            options: m.options,
+            dump: Some(m.dump),
+            pass: Some(m.pass),
+            used: m.used,
+            available: Some(m.available),
+            total: m.total,
+            use_pc: Some(m.use_pc),
+            size: None,
+            priority: None,
+        }
+    }
+
+    fn from_swap(s: SwapInfo) -> FsRow {
+        FsRow {
+            time_ms: s.time_ms,
+            kind: String::from("swap"),
+            source: s.source,
+            dest: String::new(),
+            fstype: s.kind,
+            options: String::new(),
+            dump: None,
+            pass: None,
+            used: s.used as u64,
+            available: None,
+            total: s.size as u64,
+            use_pc: None,
+            size: Some(s.size as u64),
+            priority: Some(s.priority),
+        }
+    }
+}
+
 impl SwapInfo {
     pub fn new(
         time_ms: Option<u128>,
@@ -211,8 +1033,25 @@ impl SwapInfo {
             priority,
         }
     }
+
+    pub fn to_influx(&self) -> String {
+        let fields = [
+            format!("size={}i", self.size),
+            format!("used={}i", self.used),
+            format!("priority={}i", self.priority),
+        ];
+
+        format!(
+            "swap,source={},kind={} {}{}",
+            influx_escape(&self.source),
+            influx_escape(&self.kind),
+            fields.join(","),
+            influx_time_ns(self.time_ms)
+        )
+    }
 }
 
+#[cfg(target_os = "linux")]
 pub fn statvfs(mount_point: &str) -> Option<libc::statvfs> {
     unsafe {
         let mountp = CString::new(mount_point).unwrap();
@@ -225,6 +1064,7 @@ pub fn statvfs(mount_point: &str) -> Option<libc::statvfs> {
     }
 }
 
+#[cfg(target_os = "linux")]
 pub fn fs_usage(mount_point: &str) -> (u64, u64, u64, u32) {
     match statvfs(mount_point) {
         Some(stats) => {
@@ -234,11 +1074,7 @@ pub fn fs_usage(mount_point: &str) -> (u64, u64, u64, u32) {
             let used = total - free;
             let u100 = used * 100;
             let nonroot_total = used + available;
-            let pct = if nonroot_total == 0 {
-                0
-            } else {
-                u100 / nonroot_total
-            };
+            let pct = u100.checked_div(nonroot_total).unwrap_or(0);
 
             (used, available, total, pct as u32)
         }
@@ -247,17 +1083,19 @@ pub fn fs_usage(mount_point: &str) -> (u64, u64, u64, u32) {
 }
 
 impl MountInfo {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         time_ms: Option<u128>,
         source: PathBuf,
         dest: PathBuf,
         fstype: &str,
-        options: &Vec<String>,
+        options: &[String],
         dump: i32,
         pass: i32,
+        platform: &dyn Platform,
     ) -> MountInfo {
         let dest_str = dest.to_string_lossy();
-        let (used, available, total, use_pc) = fs_usage(&dest_str);
+        let (used, available, total, use_pc) = platform.fs_usage(&dest_str);
         MountInfo {
             time_ms,
             source: String::from(source.to_string_lossy()),
@@ -272,109 +1110,248 @@ impl MountInfo {
             use_pc,
         }
     }
+
+    pub fn to_influx(&self) -> String {
+        let fields = [
+            format!("used={}i", self.used),
+            format!("available={}i", self.available),
+            format!("total={}i", self.total),
+            format!("use_pc={}i", self.use_pc),
+        ];
+
+        format!(
+            "mount,dest={},fstype={} {}{}",
+            influx_escape(&self.dest),
+            influx_escape(&self.fstype),
+            fields.join(","),
+            influx_time_ns(self.time_ms)
+        )
+    }
 }
 
-pub fn mount_stats_loop(interval: &Duration) {
+pub fn mount_stats_loop(interval: &Duration, format: Format, platform: &dyn Platform) {
+    let mut has_headers = true;
     loop {
         let time_ms = timestamp();
-        let stdout = io::stdout();
-        let handle = stdout.lock();
-        let mut wtr = WriterBuilder::new().has_headers(true).from_writer(handle);
-        match MountIter::new() {
-            Ok(mount_iter) => {
-                for mount in mount_iter {
-                    match mount {
-                        Ok(proc_mounts::MountInfo {
-                            source,
-                            dest,
-                            fstype,
-                            options,
-                            dump,
-                            pass,
-                        }) => {
-                            let mount_info = MountInfo::new(
-                                time_ms, source, dest, &fstype, &options, dump, pass,
-                            );
-
-                            match wtr.serialize(mount_info) {
-                                Ok(_) => {}
-                                Err(err) => {
-                                    eprintln!("Error writing mount info: {}", err);
-                                }
-                            }
-                        }
+
+        {
+            let stdout = io::stdout();
+            let handle = stdout.lock();
+            let mut wtr = WriterBuilder::new()
+                .has_headers(has_headers)
+                .from_writer(handle);
+
+            // Mounts and swap share the single tagged `FsRow` schema in CSV mode
+            // so both kinds of record stream through one writer without breaking
+            // header/row alignment.
+            for MountSample {
+                source,
+                dest,
+                fstype,
+                options,
+                dump,
+                pass,
+            } in platform.mounts()
+            {
+                let mount_info =
+                    MountInfo::new(time_ms, source, dest, &fstype, &options, dump, pass, platform);
+
+                match format {
+                    Format::Csv => match wtr.serialize(FsRow::from_mount(mount_info)) {
+                        Ok(_) => {}
                         Err(err) => {
-                            eprintln!("Error reading mount info: {}", err);
+                            eprintln!("Error writing mount info: {}", err);
                         }
-                    }
+                    },
+                    Format::Influx => println!("{}", mount_info.to_influx()),
                 }
             }
-            Err(err) => {
-                eprintln!("Error reading mount info: {}", err);
-            }
-        }
 
-        /*match SwapIter::new() {
-            Ok(swap_iter) => {
-                for swap in swap_iter {
-                    match swap {
-                        Ok(proc_mounts::SwapInfo {
-                            source,
-                            kind,
-                            size,
-                            used,
-                            priority,
-                        }) => {
-                            let swap_info =
-                                SwapInfo::new(time_ms, source, kind, size, used, priority);
-                            match wtr.serialize(swap_info) {
-                                Ok(_) => {}
-                                Err(err) => {
-                                    eprintln!(
-                                        "Error writing swap mount info: {} ({:?})",
-                                        err, swap_info
-                                    );
-                                }
-                            }
-                        }
+            for swap_info in collect_swaps(time_ms) {
+                match format {
+                    Format::Csv => match wtr.serialize(FsRow::from_swap(swap_info)) {
+                        Ok(_) => {}
                         Err(err) => {
-                            eprintln!("Error reading swap mount info: {}", err);
+                            eprintln!("Error writing swap mount info: {}", err);
                         }
+                    },
+                    Format::Influx => println!("{}", swap_info.to_influx()),
+                }
+            }
+
+            let _ = wtr.flush();
+        }
+
+        thread::sleep(*interval);
+        has_headers = false;
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn collect_swaps(time_ms: Option<u128>) -> Vec<SwapInfo> {
+    let mut swaps = Vec::new();
+    match SwapIter::new() {
+        Ok(swap_iter) => {
+            for swap in swap_iter {
+                match swap {
+                    Ok(proc_mounts::SwapInfo {
+                        source,
+                        kind,
+                        size,
+                        used,
+                        priority,
+                    }) => swaps.push(SwapInfo::new(time_ms, source, kind, size, used, priority)),
+                    Err(err) => {
+                        eprintln!("Error reading swap mount info: {}", err);
                     }
                 }
             }
-            Err(err) => {
-                eprintln!("Error reading swap mount info: {}", err);
+        }
+        Err(err) => {
+            eprintln!("Error reading swap mount info: {}", err);
+        }
+    }
+    swaps
+}
+
+#[cfg(not(target_os = "linux"))]
+fn collect_swaps(_time_ms: Option<u128>) -> Vec<SwapInfo> {
+    Vec::new()
+}
+
+// Walking /proc/<pid>/fd to count descriptors itself consumes descriptors, so
+// the proc collector can hit EMFILE on busy hosts. Raise the soft RLIMIT_NOFILE
+// up to the hard limit before collecting. RLIMIT_NOFILE is a Unix concept, so
+// this is a no-op on Windows.
+#[cfg(unix)]
+pub fn raise_fd_limit() {
+    unsafe {
+        let mut rlim: libc::rlimit = std::mem::zeroed();
+        if libc::getrlimit(libc::RLIMIT_NOFILE, &mut rlim) != 0 {
+            eprintln!(
+                "Error reading RLIMIT_NOFILE: {}",
+                io::Error::last_os_error()
+            );
+            return;
+        }
+
+        let before = rlim.rlim_cur;
+        // `target` is only reassigned in the macOS clamp below.
+        #[allow(unused_mut)]
+        let mut target = rlim.rlim_max;
+
+        // macOS rejects a soft limit above OPEN_MAX even when the hard limit
+        // claims to allow it, so clamp to what the kernel reports.
+        #[cfg(target_os = "macos")]
+        {
+            if let Some(open_max) = sysctl_open_max() {
+                if target > open_max {
+                    target = open_max;
+                }
             }
-        }*/
+        }
 
-        match wtr.flush() {
-            Ok(_) => {}
-            Err(_) => {}
+        rlim.rlim_cur = target;
+        if libc::setrlimit(libc::RLIMIT_NOFILE, &rlim) != 0 {
+            eprintln!(
+                "Error raising RLIMIT_NOFILE soft limit from {} to {}: {}",
+                before,
+                target,
+                io::Error::last_os_error()
+            );
+            return;
+        }
+
+        eprintln!(
+            "Raised open file descriptor soft limit from {} to {}",
+            before, target
+        );
+    }
+}
+
+#[cfg(not(unix))]
+pub fn raise_fd_limit() {}
+
+#[cfg(target_os = "macos")]
+fn sysctl_open_max() -> Option<libc::rlim_t> {
+    unsafe {
+        let mut mib = [libc::CTL_KERN, libc::KERN_MAXFILESPERPROC];
+        let mut value: libc::c_int = 0;
+        let mut size = std::mem::size_of::<libc::c_int>();
+        if libc::sysctl(
+            mib.as_mut_ptr(),
+            mib.len() as libc::c_uint,
+            &mut value as *mut _ as *mut libc::c_void,
+            &mut size,
+            std::ptr::null_mut(),
+            0,
+        ) != 0
+        {
+            None
+        } else {
+            Some(value as libc::rlim_t)
         }
-        thread::sleep(*interval);
     }
 }
 
+// SIGPIPE handling is Unix-only; Windows has no such signal, so this is a
+// no-op there.
+#[cfg(unix)]
 pub fn setup_signals() {
     unsafe {
         libc::signal(libc::SIGPIPE, libc::SIG_DFL);
     }
 }
 
+#[cfg(not(unix))]
+pub fn setup_signals() {}
+
 pub enum AppOptions {
-    SysStats { id: String, interval: Duration },
-    FdStats { interval: Duration },
-    MountStats { interval: Duration },
+    SysStats {
+        id: String,
+        interval: Duration,
+        format: Format,
+    },
+    FdStats {
+        interval: Duration,
+        format: Format,
+    },
+    MountStats {
+        interval: Duration,
+        format: Format,
+    },
+    #[cfg(target_os = "linux")]
+    NetStats {
+        interval: Duration,
+        format: Format,
+    },
+    #[cfg(target_os = "linux")]
+    DiskStats {
+        interval: Duration,
+        format: Format,
+    },
     Stop,
 }
 
 impl AppOptions {
     pub fn run(&self) {
+        let platform = default_platform();
         match self {
-            AppOptions::SysStats { id, interval } => sys_stats_loop(id, interval),
-            AppOptions::FdStats { interval } => fd_stats_loop(interval),
-            AppOptions::MountStats { interval } => mount_stats_loop(interval),
+            AppOptions::SysStats {
+                id,
+                interval,
+                format,
+            } => sys_stats_loop(id, interval, *format, platform.as_ref()),
+            AppOptions::FdStats { interval, format } => {
+                fd_stats_loop(interval, *format, platform.as_ref())
+            }
+            AppOptions::MountStats { interval, format } => {
+                mount_stats_loop(interval, *format, platform.as_ref())
+            }
+            #[cfg(target_os = "linux")]
+            AppOptions::NetStats { interval, format } => net_stats_loop(interval, *format),
+            #[cfg(target_os = "linux")]
+            AppOptions::DiskStats { interval, format } => disk_stats_loop(interval, *format),
             AppOptions::Stop => {
                 eprintln!("nothing to do");
             }
@@ -387,6 +1364,16 @@ pub fn parse_args() -> AppOptions {
         .version("0.3")
         .author("Mariano Guerra <mariano@instadeq.com>")
         .about("dumps system stats")
+        .arg(
+            Arg::with_name("format")
+                .short("f")
+                .long("format")
+                .value_name("FORMAT")
+                .global(true)
+                .possible_values(&["csv", "influx"])
+                .help("output format: csv or influx (InfluxDB line protocol)")
+                .takes_value(true),
+        )
         .subcommand(
             SubCommand::with_name("sys")
                 .about("Collect system information (CPU, Memory)")
@@ -428,6 +1415,28 @@ pub fn parse_args() -> AppOptions {
                         .help("interval in seconds between writes"),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("net")
+                .about("Collect per-interface network counters")
+                .arg(
+                    Arg::with_name("interval")
+                        .short("s")
+                        .long("interval-secs")
+                        .takes_value(true)
+                        .help("interval in seconds between writes"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("disk")
+                .about("Collect block-device I/O statistics")
+                .arg(
+                    Arg::with_name("interval")
+                        .short("s")
+                        .long("interval-secs")
+                        .takes_value(true)
+                        .help("interval in seconds between writes"),
+                ),
+        )
         .get_matches();
 
     match matches.subcommand_name() {
@@ -445,6 +1454,7 @@ pub fn parse_args() -> AppOptions {
             AppOptions::SysStats {
                 id: String::from(id),
                 interval: Duration::from_secs(interval_secs),
+                format: Format::from_arg(cmatches.value_of("format").unwrap_or("csv")),
             }
         }
         Some("proc") => {
@@ -456,8 +1466,10 @@ pub fn parse_args() -> AppOptions {
                     5
                 }
             };
+            raise_fd_limit();
             AppOptions::FdStats {
                 interval: Duration::from_secs(interval_secs),
+                format: Format::from_arg(cmatches.value_of("format").unwrap_or("csv")),
             }
         }
         Some("mount") => {
@@ -471,8 +1483,45 @@ pub fn parse_args() -> AppOptions {
             };
             AppOptions::MountStats {
                 interval: Duration::from_secs(interval_secs),
+                format: Format::from_arg(cmatches.value_of("format").unwrap_or("csv")),
+            }
+        }
+        #[cfg(target_os = "linux")]
+        Some("net") => {
+            let cmatches = matches.subcommand_matches("net").unwrap();
+            let interval_secs = match cmatches.value_of("interval").unwrap_or("5").parse::<u64>() {
+                Ok(n) => n,
+                Err(err) => {
+                    eprintln!("Invalid interval ({}), using default of 5 seconds", err);
+                    5
+                }
+            };
+            AppOptions::NetStats {
+                interval: Duration::from_secs(interval_secs),
+                format: Format::from_arg(cmatches.value_of("format").unwrap_or("csv")),
             }
         }
+        #[cfg(target_os = "linux")]
+        Some("disk") => {
+            let cmatches = matches.subcommand_matches("disk").unwrap();
+            let interval_secs = match cmatches.value_of("interval").unwrap_or("5").parse::<u64>() {
+                Ok(n) => n,
+                Err(err) => {
+                    eprintln!("Invalid interval ({}), using default of 5 seconds", err);
+                    5
+                }
+            };
+            AppOptions::DiskStats {
+                interval: Duration::from_secs(interval_secs),
+                format: Format::from_arg(cmatches.value_of("format").unwrap_or("csv")),
+            }
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        Some(name @ "net") | Some(name @ "disk") => {
+            eprintln!("the '{}' subcommand is only supported on Linux", name);
+            AppOptions::Stop
+        }
 
         None | Some(_) => AppOptions::Stop,
     }